@@ -8,10 +8,11 @@ use relative_path::RelativePath;
 use serde::Serialize;
 use std::{
   collections::HashMap,
+  fmt,
   path::{Path, PathBuf},
   str::FromStr,
 };
-use url::Url;
+use url::{ParseError, Url};
 
 lazy_static! {
   pub static ref HASH_PLACEHOLDER: String = "x".repeat(9);
@@ -23,6 +24,68 @@ lazy_static! {
     r"^https?://(esm.sh/|cdn.esm.sh/v\d+/|esm.x-static.io/v\d+/|jspm.dev/|cdn.skypack.dev/|jspm.dev/npm:|esm.run/)react(\-dom)?(@[\^|~]{0,1}[0-9a-z\.\-]+)?([/|\?].*)?$"
   )
   .unwrap();
+  pub static ref RE_PREACT_URL: Regex = Regex::new(
+    r"^https?://(esm.sh/|cdn.esm.sh/v\d+/|esm.x-static.io/v\d+/|jspm.dev/|cdn.skypack.dev/|jspm.dev/npm:|esm.run/)preact(/hooks|/compat|/test-utils|/debug|/devtools|/jsx-runtime)?(@[\^|~]{0,1}[0-9a-z\.\-]+)?([/|\?].*)?$"
+  )
+  .unwrap();
+  pub static ref RE_EXTERNAL_SPECIFIER: Regex = Regex::new(r"^(mailto:|data:|[a-z]+:)").unwrap();
+}
+
+/// the jsx runtime an app is built against, used to decide which
+/// framework (and CDN package names) `Resolver::resolve` pins versions for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JsxRuntime {
+  React,
+  Preact,
+}
+
+impl Default for JsxRuntime {
+  fn default() -> Self {
+    JsxRuntime::React
+  }
+}
+
+impl From<&str> for JsxRuntime {
+  fn from(s: &str) -> Self {
+    match s {
+      "preact" => JsxRuntime::Preact,
+      _ => JsxRuntime::React,
+    }
+  }
+}
+
+/// an error produced while resolving an import/export url, carrying enough
+/// context to report it back to the user instead of panicking the compiler.
+#[derive(Debug)]
+pub enum ResolutionError {
+  InvalidUrl(ParseError),
+  InvalidBaseUrl,
+  InvalidPath(PathBuf),
+  ImportPrefixMissing { specifier: String, referrer: String },
+}
+
+impl fmt::Display for ResolutionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ResolutionError::InvalidUrl(err) => write!(f, "invalid url: {}", err),
+      ResolutionError::InvalidBaseUrl => write!(f, "invalid base url: missing host"),
+      ResolutionError::InvalidPath(path) => write!(f, "invalid path: {}", path.display()),
+      ResolutionError::ImportPrefixMissing { specifier, referrer } => write!(
+        f,
+        "relative import '{}' not prefixed with / or ./ or ../ from '{}'",
+        specifier, referrer
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ResolutionError {}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyLoc {
+  pub start: usize,
+  pub end: usize,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -30,6 +93,8 @@ lazy_static! {
 pub struct DependencyDescriptor {
   pub specifier: String,
   pub is_dynamic: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub loc: Option<DependencyLoc>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -60,7 +125,11 @@ pub struct Resolver {
   // private
   import_map: ImportMap,
   aleph_pkg_uri: Option<String>,
+  jsx_runtime: JsxRuntime,
   react_version: Option<String>,
+  global_version: Option<String>,
+  graph_versions: HashMap<String, String>,
+  resolve_remote_deps: bool,
 }
 
 impl Resolver {
@@ -68,9 +137,13 @@ impl Resolver {
     specifier: &str,
     import_map: ImportHashMap,
     aleph_pkg_uri: Option<String>,
+    jsx_runtime: JsxRuntime,
     react_version: Option<String>,
     bundle_mode: bool,
     bundled_modules: Vec<String>,
+    global_version: Option<String>,
+    graph_versions: HashMap<String, String>,
+    resolve_remote_deps: bool,
   ) -> Self {
     let mut set = IndexSet::<String>::new();
     for url in bundled_modules {
@@ -84,9 +157,35 @@ impl Resolver {
       inline_styles: HashMap::new(),
       import_map: ImportMap::from_hashmap(import_map),
       aleph_pkg_uri,
+      jsx_runtime,
       react_version,
       bundle_mode,
       bundled_modules: set,
+      global_version,
+      graph_versions,
+      resolve_remote_deps,
+    }
+  }
+
+  /// record a dependency in the dep graph, unless `rel` marks it as the
+  /// module's own specifier (i.e. a self-reference that shouldn't be tracked).
+  fn push_dep(
+    &mut self,
+    specifier: String,
+    is_dynamic: bool,
+    loc: Option<DependencyLoc>,
+    rel: &Option<String>,
+  ) {
+    let update_dep_graph = match rel {
+      Some(rel) => !rel.eq("."),
+      None => true,
+    };
+    if update_dep_graph {
+      self.dep_graph.push(DependencyDescriptor {
+        specifier,
+        is_dynamic,
+        loc,
+      });
     }
   }
 
@@ -106,7 +205,7 @@ impl Resolver {
   //  - `./button.tsx` -> `./button.tsx`
   //  - `/components/foo/./logo.tsx` -> `/components/foo/logo.tsx`
   //  - `/components/foo/../logo.tsx` -> `/components/logo.tsx`
-  pub fn fix_import_url(&self, url: &str) -> String {
+  pub fn fix_import_url(&self, url: &str) -> Result<String, ResolutionError> {
     let is_remote = is_remote_url(url);
     if !is_remote {
       let mut url = url;
@@ -118,14 +217,12 @@ impl Resolver {
         url = url.trim_start_matches("..");
         root = Path::new("..");
       }
-      return RelativePath::new(url)
-        .normalize()
-        .to_path(root)
+      let path = RelativePath::new(url).normalize().to_path(root);
+      return path
         .to_slash()
-        .unwrap()
-        .to_owned();
+        .ok_or_else(|| ResolutionError::InvalidPath(path.clone()));
     }
-    let url = Url::from_str(url).unwrap();
+    let url = Url::from_str(url).map_err(ResolutionError::InvalidUrl)?;
     let path = Path::new(url.path());
     let mut path_buf = path.to_owned();
     let mut ext = ".".to_owned();
@@ -164,7 +261,7 @@ impl Resolver {
     if url.scheme() == "http" {
       p.push_str("http_");
     }
-    p.push_str(url.host_str().unwrap());
+    p.push_str(url.host_str().ok_or(ResolutionError::InvalidBaseUrl)?);
     match url.port() {
       Some(port) => {
         p.push('_');
@@ -172,8 +269,12 @@ impl Resolver {
       }
       _ => {}
     }
-    p.push_str(path_buf.to_str().unwrap());
-    p
+    p.push_str(
+      path_buf
+        .to_str()
+        .ok_or_else(|| ResolutionError::InvalidPath(path_buf.clone()))?,
+    );
+    Ok(p)
   }
 
   /// resolve import/export url.
@@ -185,24 +286,36 @@ impl Resolver {
   // - `../styles/app.css` -> `/styles/app.css.{HASH}.js`
   // - `@/components/logo.tsx` -> `/components/logo.{HASH}.js`
   // - `~/components/logo.tsx` -> `/components/logo.{HASH}.js`
-  pub fn resolve(&mut self, url: &str, is_dynamic: bool, rel: Option<String>) -> (String, String) {
+  pub fn resolve(
+    &mut self,
+    url: &str,
+    is_dynamic: bool,
+    rel: Option<String>,
+    loc: Option<DependencyLoc>,
+  ) -> Result<(String, String), ResolutionError> {
     // apply import map
     let url = self.import_map.resolve(self.specifier.as_str(), url);
+    if is_external_url(url.as_str()) {
+      let fixed_url: String = url.into();
+      self.push_dep(fixed_url.clone(), is_dynamic, loc, &rel);
+      return Ok((fixed_url.clone(), fixed_url));
+    }
     let mut fixed_url: String = if is_remote_url(url.as_str()) {
       url.into()
     } else {
       if self.specifier_is_remote {
-        let mut new_url = Url::from_str(self.specifier.as_str()).unwrap();
+        let mut new_url =
+          Url::from_str(self.specifier.as_str()).map_err(ResolutionError::InvalidUrl)?;
         if url.starts_with("/") {
           new_url.set_path(url.as_str());
         } else {
           let mut buf = PathBuf::from(new_url.path());
           buf.pop();
-          buf.push(url);
-          let path = "/".to_owned()
-            + RelativePath::new(buf.to_slash().unwrap().as_str())
-              .normalize()
-              .as_str();
+          buf.push(&url);
+          let slash = buf
+            .to_slash()
+            .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+          let path = "/".to_owned() + RelativePath::new(slash.as_str()).normalize().as_str();
           new_url.set_path(path.as_str());
         }
         new_url.as_str().into()
@@ -213,14 +326,19 @@ impl Resolver {
           url.trim_start_matches("@").into()
         } else if url.starts_with("~/") {
           url.trim_start_matches("~").into()
-        } else {
+        } else if url.starts_with("./") || url.starts_with("../") {
           let mut buf = PathBuf::from(self.specifier.as_str());
           buf.pop();
-          buf.push(url);
-          "/".to_owned()
-            + RelativePath::new(buf.to_slash().unwrap().as_str())
-              .normalize()
-              .as_str()
+          buf.push(&url);
+          let slash = buf
+            .to_slash()
+            .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+          "/".to_owned() + RelativePath::new(slash.as_str()).normalize().as_str()
+        } else {
+          return Err(ResolutionError::ImportPrefixMissing {
+            specifier: url.into(),
+            referrer: self.specifier.clone(),
+          });
         }
       }
     };
@@ -234,10 +352,14 @@ impl Resolver {
         );
       }
     }
-    // fix react/react-dom url
+    // fix react/react-dom (or preact and its submodules like preact/compat) url
     if let Some(version) = &self.react_version {
-      if RE_REACT_URL.is_match(fixed_url.as_str()) {
-        let caps = RE_REACT_URL.captures(fixed_url.as_str()).unwrap();
+      let (re, pkg_name) = match self.jsx_runtime {
+        JsxRuntime::Preact => (&*RE_PREACT_URL, "preact"),
+        JsxRuntime::React => (&*RE_REACT_URL, "react"),
+      };
+      if re.is_match(fixed_url.as_str()) {
+        let caps = re.captures(fixed_url.as_str()).unwrap();
         let mut host = caps.get(1).map_or("", |m| m.as_str());
         let non_esm_sh_cdn = !host.starts_with("esm.sh/")
           && !host.starts_with("cdn.esm.sh/")
@@ -249,62 +371,96 @@ impl Resolver {
         let ver = caps.get(3).map_or("", |m| m.as_str());
         let path = caps.get(4).map_or("", |m| m.as_str());
         if non_esm_sh_cdn || ver != version {
-          fixed_url = format!("https://{}react{}@{}{}", host, pkg, version, path);
+          fixed_url = match self.jsx_runtime {
+            // preact's capture group 2 is a submodule path (`/hooks`, `/compat`, ...),
+            // not a name suffix like react-dom's `-dom`, so the version goes right
+            // after the bare package name and the submodule path comes after that.
+            JsxRuntime::Preact => {
+              format!("https://{}{}@{}{}{}", host, pkg_name, version, pkg, path)
+            }
+            JsxRuntime::React => {
+              format!("https://{}{}{}@{}{}", host, pkg_name, pkg, version, path)
+            }
+          };
         }
       }
     }
     let is_remote = is_remote_url(fixed_url.as_str());
+    if is_remote && !self.resolve_remote_deps {
+      self.push_dep(fixed_url.clone(), is_dynamic, loc, &rel);
+      return Ok((fixed_url.clone(), fixed_url));
+    }
     let mut resolved_path = if is_remote {
       if self.specifier_is_remote {
-        let mut buf = PathBuf::from(self.fix_import_url(self.specifier.as_str()));
+        let mut buf = PathBuf::from(self.fix_import_url(self.specifier.as_str())?);
         buf.pop();
-        diff_paths(
-          self.fix_import_url(fixed_url.as_str()),
-          buf.to_slash().unwrap(),
-        )
-        .unwrap()
+        let base = buf
+          .to_slash()
+          .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+        let target = self.fix_import_url(fixed_url.as_str())?;
+        diff_paths(&target, base)
+          .ok_or_else(|| ResolutionError::InvalidPath(PathBuf::from(target)))?
       } else {
         let mut buf = PathBuf::from(self.specifier.as_str());
         buf.pop();
-        diff_paths(
-          self.fix_import_url(fixed_url.as_str()),
-          buf.to_slash().unwrap(),
-        )
-        .unwrap()
+        let base = buf
+          .to_slash()
+          .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+        let target = self.fix_import_url(fixed_url.as_str())?;
+        diff_paths(&target, base)
+          .ok_or_else(|| ResolutionError::InvalidPath(PathBuf::from(target)))?
       }
     } else {
       if self.specifier_is_remote {
-        let mut new_url = Url::from_str(self.specifier.as_str()).unwrap();
+        let mut new_url =
+          Url::from_str(self.specifier.as_str()).map_err(ResolutionError::InvalidUrl)?;
         if fixed_url.starts_with("/") {
           new_url.set_path(fixed_url.as_str());
         } else {
           let mut buf = PathBuf::from(new_url.path());
           buf.pop();
           buf.push(fixed_url.as_str());
-          let path = "/".to_owned()
-            + RelativePath::new(buf.to_slash().unwrap().as_str())
-              .normalize()
-              .as_str();
+          let slash = buf
+            .to_slash()
+            .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+          let path = "/".to_owned() + RelativePath::new(slash.as_str()).normalize().as_str();
           new_url.set_path(path.as_str());
         }
-        let mut buf = PathBuf::from(self.fix_import_url(self.specifier.as_str()));
+        let mut buf = PathBuf::from(self.fix_import_url(self.specifier.as_str())?);
         buf.pop();
-        diff_paths(
-          self.fix_import_url(new_url.as_str()),
-          buf.to_slash().unwrap(),
-        )
-        .unwrap()
+        let base = buf
+          .to_slash()
+          .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+        let target = self.fix_import_url(new_url.as_str())?;
+        diff_paths(&target, base)
+          .ok_or_else(|| ResolutionError::InvalidPath(PathBuf::from(target)))?
       } else {
         if fixed_url.starts_with("/") {
           let mut buf = PathBuf::from(self.specifier.as_str());
           buf.pop();
-          diff_paths(fixed_url.clone(), buf.to_slash().unwrap()).unwrap()
+          let base = buf
+            .to_slash()
+            .ok_or_else(|| ResolutionError::InvalidPath(buf.clone()))?;
+          diff_paths(fixed_url.clone(), base)
+            .ok_or_else(|| ResolutionError::InvalidPath(PathBuf::from(fixed_url.clone())))?
         } else {
           PathBuf::from(fixed_url.clone())
         }
       }
     };
-    // fix extension & add hash placeholder
+    // lookup the graph version for cache busting; a local module is versioned
+    // either by its own entry in `graph_versions` or by the `global_version`
+    // fallback, otherwise we fall back to the legacy hash placeholder below
+    let graph_version = if !is_remote && !self.specifier_is_remote {
+      self
+        .graph_versions
+        .get(fixed_url.as_str())
+        .or(self.global_version.as_ref())
+        .cloned()
+    } else {
+      None
+    };
+    // fix extension & add hash placeholder (only when no graph version is configured)
     match resolved_path.extension() {
       Some(os_str) => match os_str.to_str() {
         Some(s) => match s {
@@ -316,7 +472,7 @@ impl Resolver {
               .unwrap()
               .trim_end_matches(s)
               .to_owned();
-            if !is_remote && !self.specifier_is_remote {
+            if !is_remote && !self.specifier_is_remote && graph_version.is_none() {
               filename.push_str(HASH_PLACEHOLDER.as_str());
               filename.push('.');
             }
@@ -332,8 +488,11 @@ impl Resolver {
                 .unwrap()
                 .to_owned();
               filename.push('.');
-              filename.push_str(HASH_PLACEHOLDER.as_str());
-              filename.push_str(".js");
+              if graph_version.is_none() {
+                filename.push_str(HASH_PLACEHOLDER.as_str());
+                filename.push('.');
+              }
+              filename.push_str("js");
               resolved_path.set_file_name(filename);
             }
           }
@@ -342,21 +501,17 @@ impl Resolver {
       },
       None => {}
     };
-    let update_dep_graph = match rel {
-      Some(ref rel) => !rel.eq("."),
-      None => true,
-    };
-    if update_dep_graph {
-      self.dep_graph.push(DependencyDescriptor {
-        specifier: fixed_url.clone(),
-        is_dynamic,
-      });
+    self.push_dep(fixed_url.clone(), is_dynamic, loc, &rel);
+    let mut path = resolved_path
+      .to_slash()
+      .ok_or_else(|| ResolutionError::InvalidPath(resolved_path.clone()))?;
+    if let Some(version) = graph_version {
+      path = format!("{}?v={}", path, version);
     }
-    let path = resolved_path.to_slash().unwrap();
     if !path.starts_with("./") && !path.starts_with("../") && !path.starts_with("/") {
-      return (format!("./{}", path), fixed_url);
+      return Ok((format!("./{}", path), fixed_url));
     }
-    (path, fixed_url)
+    Ok((path, fixed_url))
   }
 }
 
@@ -364,6 +519,12 @@ pub fn is_remote_url(url: &str) -> bool {
   return url.starts_with("https://") || url.starts_with("http://");
 }
 
+/// non-http protocol specifiers (`data:`, `npm:`, `node:`, `mailto:`, etc) that
+/// should pass through untouched instead of being mistaken for a relative path.
+pub fn is_external_url(url: &str) -> bool {
+  !is_remote_url(url) && RE_EXTERNAL_SPECIFIER.is_match(url)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -376,43 +537,47 @@ mod tests {
       "/app.tsx",
       ImportHashMap::default(),
       None,
+      JsxRuntime::default(),
       None,
       false,
       vec![],
+      None,
+      HashMap::new(),
+      true,
     );
     assert_eq!(
-      resolver.fix_import_url("https://esm.sh/react"),
+      resolver.fix_import_url("https://esm.sh/react").unwrap(),
       "/-/esm.sh/react.js"
     );
     assert_eq!(
-      resolver.fix_import_url("https://esm.sh/react@17.0.1?target=es2015&dev"),
+      resolver.fix_import_url("https://esm.sh/react@17.0.1?target=es2015&dev").unwrap(),
       "/-/esm.sh/react@17.0.1_target=es2015&dev.js"
     );
     assert_eq!(
-      resolver.fix_import_url("http://localhost:8080/mod"),
+      resolver.fix_import_url("http://localhost:8080/mod").unwrap(),
       "/-/http_localhost_8080/mod.js"
     );
     assert_eq!(
-      resolver.fix_import_url("/components/foo/./logo.tsx"),
+      resolver.fix_import_url("/components/foo/./logo.tsx").unwrap(),
       "/components/foo/logo.tsx"
     );
     assert_eq!(
-      resolver.fix_import_url("/components/foo/../logo.tsx"),
+      resolver.fix_import_url("/components/foo/../logo.tsx").unwrap(),
       "/components/logo.tsx"
     );
     assert_eq!(
-      resolver.fix_import_url("/components/../foo/logo.tsx"),
+      resolver.fix_import_url("/components/../foo/logo.tsx").unwrap(),
       "/foo/logo.tsx"
     );
     assert_eq!(
-      resolver.fix_import_url("/components/logo.tsx"),
+      resolver.fix_import_url("/components/logo.tsx").unwrap(),
       "/components/logo.tsx"
     );
     assert_eq!(
-      resolver.fix_import_url("../components/logo.tsx"),
+      resolver.fix_import_url("../components/logo.tsx").unwrap(),
       "../components/logo.tsx"
     );
-    assert_eq!(resolver.fix_import_url("./button.tsx"), "./button.tsx");
+    assert_eq!(resolver.fix_import_url("./button.tsx").unwrap(), "./button.tsx");
   }
 
   #[test]
@@ -433,19 +598,23 @@ mod tests {
         scopes: HashMap::new(),
       },
       None,
+      JsxRuntime::default(),
       Some("17.0.1".into()),
       false,
       vec![],
+      None,
+      HashMap::new(),
+      true,
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react", false, None),
+      resolver.resolve("https://esm.sh/react", false, None, None).unwrap(),
       (
         "../-/esm.sh/react@17.0.1.js".into(),
         "https://esm.sh/react@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react-refresh", false, None),
+      resolver.resolve("https://esm.sh/react-refresh", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-refresh.js".into(),
         "https://esm.sh/react-refresh".into()
@@ -455,92 +624,93 @@ mod tests {
       resolver.resolve(
         "https://deno.land/x/aleph/framework/react/link.ts",
         false,
+        None,
         None
-      ),
+      ).unwrap(),
       (
         "../-/http_localhost_2020/framework/react/link.js".into(),
         "http://localhost:2020/framework/react/link.ts".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react@16", false, None),
+      resolver.resolve("https://esm.sh/react@16", false, None, None).unwrap(),
       (
         "../-/esm.sh/react@17.0.1.js".into(),
         "https://esm.sh/react@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react-dom", false, None),
+      resolver.resolve("https://esm.sh/react-dom", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-dom@17.0.1.js".into(),
         "https://esm.sh/react-dom@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react-dom@16.14.0", false, None),
+      resolver.resolve("https://esm.sh/react-dom@16.14.0", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-dom@17.0.1.js".into(),
         "https://esm.sh/react-dom@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react-dom/server", false, None),
+      resolver.resolve("https://esm.sh/react-dom/server", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-dom@17.0.1/server.js".into(),
         "https://esm.sh/react-dom@17.0.1/server".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://esm.sh/react-dom@16.13.1/server", false, None),
+      resolver.resolve("https://esm.sh/react-dom@16.13.1/server", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-dom@17.0.1/server.js".into(),
         "https://esm.sh/react-dom@17.0.1/server".into()
       )
     );
     assert_eq!(
-      resolver.resolve("react-dom/server", false, None),
+      resolver.resolve("react-dom/server", false, None, None).unwrap(),
       (
         "../-/esm.sh/react-dom@17.0.1/server.js".into(),
         "https://esm.sh/react-dom@17.0.1/server".into()
       )
     );
     assert_eq!(
-      resolver.resolve("react", false, None),
+      resolver.resolve("react", false, None, None).unwrap(),
       (
         "../-/esm.sh/react@17.0.1.js".into(),
         "https://esm.sh/react@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("https://deno.land/x/aleph/mod.ts", false, None),
+      resolver.resolve("https://deno.land/x/aleph/mod.ts", false, None, None).unwrap(),
       (
         "../-/http_localhost_2020/mod.js".into(),
         "http://localhost:2020/mod.ts".into()
       )
     );
     assert_eq!(
-      resolver.resolve("../components/logo.tsx", false, None),
+      resolver.resolve("../components/logo.tsx", false, None, None).unwrap(),
       (
         format!("../components/logo.{}.js", HASH_PLACEHOLDER.as_str()),
         "/components/logo.tsx".into()
       )
     );
     assert_eq!(
-      resolver.resolve("../styles/app.css", false, None),
+      resolver.resolve("../styles/app.css", false, None, None).unwrap(),
       (
         format!("../styles/app.css.{}.js", HASH_PLACEHOLDER.as_str()),
         "/styles/app.css".into()
       )
     );
     assert_eq!(
-      resolver.resolve("@/components/logo.tsx", false, None),
+      resolver.resolve("@/components/logo.tsx", false, None, None).unwrap(),
       (
         format!("../components/logo.{}.js", HASH_PLACEHOLDER.as_str()),
         "/components/logo.tsx".into()
       )
     );
     assert_eq!(
-      resolver.resolve("~/components/logo.tsx", false, None),
+      resolver.resolve("~/components/logo.tsx", false, None, None).unwrap(),
       (
         format!("../components/logo.{}.js", HASH_PLACEHOLDER.as_str()),
         "/components/logo.tsx".into()
@@ -548,36 +718,101 @@ mod tests {
     );
   }
 
+  #[test]
+  fn resolve_local_with_graph_version() {
+    let mut graph_versions: HashMap<String, String> = HashMap::new();
+    graph_versions.insert("/components/logo.tsx".into(), "100".into());
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      None,
+      false,
+      vec![],
+      Some("1".into()),
+      graph_versions,
+      true,
+    );
+    assert_eq!(
+      resolver.resolve("../components/logo.tsx", false, None, None).unwrap(),
+      (
+        "../components/logo.js?v=100".into(),
+        "/components/logo.tsx".into()
+      )
+    );
+    assert_eq!(
+      resolver.resolve("../styles/app.css", false, None, None).unwrap(),
+      (
+        "../styles/app.css.js?v=1".into(),
+        "/styles/app.css".into()
+      )
+    );
+  }
+
+  #[test]
+  fn resolve_records_dep_loc() {
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      None,
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      true,
+    );
+    resolver
+      .resolve(
+        "../components/logo.tsx",
+        false,
+        None,
+        Some(DependencyLoc { start: 10, end: 36 }),
+      )
+      .unwrap();
+    assert_eq!(
+      resolver.dep_graph[0].loc,
+      Some(DependencyLoc { start: 10, end: 36 })
+    );
+  }
+
   #[test]
   fn resolve_remote_1() {
     let mut resolver = Resolver::new(
       "https://esm.sh/react-dom",
       ImportHashMap::default(),
       None,
+      JsxRuntime::default(),
       Some("17.0.1".into()),
       false,
       vec![],
+      None,
+      HashMap::new(),
+      true,
     );
     assert_eq!(
       resolver.resolve(
         "https://cdn.esm.sh/react@17.0.1/es2020/react.js",
         false,
+        None,
         None
-      ),
+      ).unwrap(),
       (
         "../cdn.esm.sh/react@17.0.1/es2020/react.js".into(),
         "https://cdn.esm.sh/react@17.0.1/es2020/react.js".into()
       )
     );
     assert_eq!(
-      resolver.resolve("./react", false, None),
+      resolver.resolve("./react", false, None, None).unwrap(),
       (
         "./react@17.0.1.js".into(),
         "https://esm.sh/react@17.0.1".into()
       )
     );
     assert_eq!(
-      resolver.resolve("/react", false, None),
+      resolver.resolve("/react", false, None, None).unwrap(),
       (
         "./react@17.0.1.js".into(),
         "https://esm.sh/react@17.0.1".into()
@@ -591,28 +826,200 @@ mod tests {
       "https://esm.sh/preact/hooks",
       ImportHashMap::default(),
       None,
+      JsxRuntime::default(),
       None,
       false,
       vec![],
+      None,
+      HashMap::new(),
+      true,
     );
     assert_eq!(
       resolver.resolve(
         "https://cdn.esm.sh/preact@10.5.7/es2020/preact.js",
         false,
+        None,
         None
-      ),
+      ).unwrap(),
       (
         "../../cdn.esm.sh/preact@10.5.7/es2020/preact.js".into(),
         "https://cdn.esm.sh/preact@10.5.7/es2020/preact.js".into()
       )
     );
     assert_eq!(
-      resolver.resolve("../preact", false, None),
+      resolver.resolve("../preact", false, None, None).unwrap(),
       ("../preact.js".into(), "https://esm.sh/preact".into())
     );
     assert_eq!(
-      resolver.resolve("/preact", false, None),
+      resolver.resolve("/preact", false, None, None).unwrap(),
       ("../preact.js".into(), "https://esm.sh/preact".into())
     );
   }
+
+  #[test]
+  fn resolve_external_specifiers() {
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      None,
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      true,
+    );
+    assert_eq!(
+      resolver.resolve("npm:react@18", false, None, None).unwrap(),
+      ("npm:react@18".into(), "npm:react@18".into())
+    );
+    assert_eq!(
+      resolver.resolve("node:fs", false, None, None).unwrap(),
+      ("node:fs".into(), "node:fs".into())
+    );
+    assert_eq!(
+      resolver
+        .resolve("data:text/javascript,export default 1", false, None, None)
+        .unwrap(),
+      (
+        "data:text/javascript,export default 1".into(),
+        "data:text/javascript,export default 1".into()
+      )
+    );
+    assert_eq!(
+      resolver.resolve("mailto:a@b.com", false, None, None).unwrap(),
+      ("mailto:a@b.com".into(), "mailto:a@b.com".into())
+    );
+  }
+
+  #[test]
+  fn resolve_remote_deps_disabled() {
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      Some("17.0.1".into()),
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      false,
+    );
+    assert_eq!(
+      resolver.resolve("https://esm.sh/react", false, None, None).unwrap(),
+      (
+        "https://esm.sh/react@17.0.1".into(),
+        "https://esm.sh/react@17.0.1".into()
+      )
+    );
+    assert_eq!(
+      resolver.resolve("../components/logo.tsx", false, None, None).unwrap(),
+      (
+        format!("../components/logo.{}.js", HASH_PLACEHOLDER.as_str()),
+        "/components/logo.tsx".into()
+      )
+    );
+  }
+
+  #[test]
+  fn resolve_preact_runtime() {
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::Preact,
+      Some("10.5.7".into()),
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      true,
+    );
+    assert_eq!(
+      resolver.resolve("https://esm.sh/preact", false, None, None).unwrap(),
+      (
+        "../-/esm.sh/preact@10.5.7.js".into(),
+        "https://esm.sh/preact@10.5.7".into()
+      )
+    );
+    assert_eq!(
+      resolver.resolve("https://esm.sh/preact@10.4.1/hooks", false, None, None).unwrap(),
+      (
+        "../-/esm.sh/preact@10.5.7/hooks.js".into(),
+        "https://esm.sh/preact@10.5.7/hooks".into()
+      )
+    );
+    assert_eq!(
+      resolver.resolve("https://esm.sh/preact/compat/server", false, None, None).unwrap(),
+      (
+        "../-/esm.sh/preact@10.5.7/compat/server.js".into(),
+        "https://esm.sh/preact@10.5.7/compat/server".into()
+      )
+    );
+  }
+
+  #[test]
+  fn resolve_err_import_prefix_missing() {
+    let mut resolver = Resolver::new(
+      "/pages/index.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      None,
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      true,
+    );
+    let err = resolver.resolve("some-bare-pkg", false, None, None).unwrap_err();
+    assert!(matches!(err, ResolutionError::ImportPrefixMissing { .. }));
+    assert_eq!(
+      err.to_string(),
+      "relative import 'some-bare-pkg' not prefixed with / or ./ or ../ from '/pages/index.tsx'"
+    );
+  }
+
+  #[test]
+  fn fix_import_url_err_invalid_url() {
+    let resolver = Resolver::new(
+      "/app.tsx",
+      ImportHashMap::default(),
+      None,
+      JsxRuntime::default(),
+      None,
+      false,
+      vec![],
+      None,
+      HashMap::new(),
+      true,
+    );
+    let err = resolver.fix_import_url("https://").unwrap_err();
+    assert!(matches!(err, ResolutionError::InvalidUrl(_)));
+  }
+
+  #[test]
+  fn resolution_error_invalid_base_url_display() {
+    // `InvalidBaseUrl` guards `Url::host_str()` in `fix_import_url`, which the
+    // `url` crate already refuses to parse as http(s) without a host, so it
+    // can't be reached through the public API; check its Display text directly.
+    assert_eq!(
+      ResolutionError::InvalidBaseUrl.to_string(),
+      "invalid base url: missing host"
+    );
+  }
+
+  #[test]
+  fn resolution_error_invalid_path_display() {
+    // `InvalidPath` guards `to_slash()`/`diff_paths()` failures: non-UTF8
+    // components, which the resolver's String-typed public API can't produce,
+    // and a base path that `diff_paths()` can't walk (e.g. a referrer like
+    // `/../index.tsx`); check its Display text directly.
+    assert_eq!(
+      ResolutionError::InvalidPath(PathBuf::from("/components/logo.tsx")).to_string(),
+      "invalid path: /components/logo.tsx"
+    );
+  }
 }